@@ -1,93 +1,114 @@
 use core::ffi::c_size_t;
 use std::{
-  ffi::{c_void, CStr},
+  collections::HashMap,
+  ffi::{c_char, c_void, CStr},
   marker::PhantomData,
   mem::MaybeUninit,
+  sync::mpsc::{self, Receiver, TryRecvError},
+  task::Poll,
+  thread::JoinHandle,
+  time::Duration,
 };
 
 use libc::{IP_TOS, NI_MAXHOST};
 
 use crate::bindings::*;
 
-type Result<'a, T> = core::result::Result<T, PingError<'a>>;
+type Result<T> = core::result::Result<T, PingError>;
 
 /// Error Type of our Ping structure.
-/// The lifetime is tied to the lifetime of the Ping object
-/// because the error references a C-String stored in the object. And
-/// multiple errors are written to the same buffer address,
-/// reference: https://github.com/octo/liboping/blob/master/src/liboping.c
-/// Therefore construction of a second [PingError] invalidates the first one becaue
-/// the error msg might no longer be valid.
+///
+/// The message is copied out of the C-String returned by `ping_get_error` and
+/// owned by the [PingError] itself, so it no longer aliases the buffer inside
+/// the [Ping] object. This makes [PingError] a plain owned, `'static` type that
+/// implements [std::error::Error] and composes with Rust's `?` operator.
 ///
 /// ## Example
-/// ```compile_fail
+/// ```no_run
+/// use std::error::Error;
 /// use rping::Ping;
-/// use std::ffi::CStr;
-/// let mut p = Ping::new();
-/// let s1: &'static CStr = unsafe {
-///   std::mem::transmute("aaaaunjojlk.com")
-/// };
-/// let s2: &'static CStr = unsafe {
-///   std::mem::transmute("src.com")
-/// };
-/// let r1 = p.add_host(s1);
-/// let r2 = p.add_host(s2); // Error! p is mutably borrowed by r1
-///
-/// println!("{:?}", r1);
-/// ```
-///
-/// Due to this fact, [PingError] doesn't play well with RUst's ? operator if it is
-/// generated by a local [Ping] object, and users are expected to implement their own error
-/// handling on top of it.
+/// use byte_strings::c;
 ///
-/// ## Example
-/// ```compile_fail
 /// fn main() -> Result<(), Box<dyn Error>> {
-///   use rping::Ping;
-///   use std::ffi::CStr;
 ///   let mut p = Ping::new();
-///   let s: &'static CStr = unsafe {
-///     std::mem::transmute("github.com");
-///   }
-///   p.add_host(s)?; // Error! The PingError cannot be propogated outside of the function after p gets dropped.
+///   p.add_host(c!("github.com"))?; // bubbles up across the function boundary
 ///   Ok(())
 /// }
+/// ```
 #[derive(Debug)]
-pub struct PingError<'a> {
-  // this points inside the Ping object.
-  msg: &'a CStr,
+pub struct PingError {
+  msg: String,
 }
 
-impl<'a> PingError<'a> {
-  fn new(msg: &'a CStr) -> Self {
+impl PingError {
+  fn new(msg: String) -> Self {
     Self { msg }
   }
 }
 
-impl<'a> core::fmt::Display for PingError<'a> {
+impl core::fmt::Display for PingError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{:?}", self)
+    write!(f, "{}", self.msg)
   }
 }
 
-// impl<'a> std::error::Error for PingError<'a> {
-//   fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-//     None
-//   }
+impl std::error::Error for PingError {}
+
+/// Address family of a ping session, used with [`Ping::set_address_family`] to
+/// force IPv4 or IPv6 targets. Maps onto the C `AF_INET`/`AF_INET6` constants
+/// that liboping's `PING_OPT_AF` expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+  /// Let liboping pick the family based on the resolved host (`AF_UNSPEC`).
+  Unspec,
+  /// IPv4 (`AF_INET`).
+  Ipv4,
+  /// IPv6 (`AF_INET6`).
+  Ipv6,
+}
+
+impl AddressFamily {
+  /// The raw `AF_*` value understood by the C library.
+  fn as_raw(self) -> i32 {
+    match self {
+      AddressFamily::Unspec => libc::AF_UNSPEC,
+      AddressFamily::Ipv4 => libc::AF_INET,
+      AddressFamily::Ipv6 => libc::AF_INET6,
+    }
+  }
 
-//   fn description(&self) -> &str {
-//     self.msg.to_str().unwrap()
-//   }
+  /// Interpret a raw `AF_*` value, defaulting to [`AddressFamily::Unspec`] for
+  /// anything liboping would not return for an ICMP host.
+  fn from_raw(af: i32) -> Self {
+    match af {
+      libc::AF_INET => AddressFamily::Ipv4,
+      libc::AF_INET6 => AddressFamily::Ipv6,
+      _ => AddressFamily::Unspec,
+    }
+  }
+}
 
-//   fn cause(&self) -> Option<&dyn std::error::Error> {
-//     self.source()
-//   }
-// }
+/// A `*mut pingobj_t` that we promise to hand to exactly one thread at a time.
+///
+/// liboping's object is not thread safe, so this wrapper only exists to move
+/// the pointer onto the worker thread spawned by [`Ping::send_poll`]. Every
+/// other `Ping` method joins that worker before touching the object (see
+/// [`Ping::block_on_pending`]), so it is only ever accessed by one thread at a
+/// time.
+struct SendPtr(*mut pingobj_t);
+
+// SAFETY: see [`Ping::block_on_pending`] — the object is only ever accessed by
+// one thread at a time.
+unsafe impl Send for SendPtr {}
 
 /// Safe Rust Wrappers around `pingobj_t` in [liboping](https://noping.cc/)
 #[derive(Debug)]
 pub struct Ping {
   inner: *mut pingobj_t,
+  /// Join handle and result channel of an in-flight [`Ping::send_poll`] worker,
+  /// if any. While this is `Some`, the worker thread owns `inner`, so every
+  /// other operation must first drain it via [`Ping::block_on_pending`].
+  pending: Option<(JoinHandle<()>, Receiver<i32>)>,
 }
 
 impl Ping {
@@ -98,17 +119,33 @@ impl Ping {
     unsafe {
       Self {
         inner: ping_construct(),
+        pending: None,
       }
     }
   }
 
+  /// Block until any in-flight [`Ping::send_poll`] worker has finished and
+  /// joined, so `inner` is exclusively ours again. Called at the head of every
+  /// other method that touches the `pingobj_t`, which is what actually makes
+  /// concurrent access impossible — a doc comment alone would not.
+  fn block_on_pending(&mut self) {
+    if let Some((handle, rx)) = self.pending.take() {
+      // `recv` returns once the worker has sent its result, i.e. after
+      // `ping_send` has returned; the join then guarantees the thread is gone.
+      let _ = rx.recv();
+      let _ = handle.join();
+    }
+  }
+
   unsafe fn map_err(&mut self, ret: i32) -> Result<()> {
     if ret >= 0 {
       Ok(())
     } else {
       let ptr = ping_get_error(self.inner);
-      let c_str = CStr::from_ptr(ptr);
-      Err(PingError::new(c_str))
+      // Copy the message out of the Ping object's buffer so the error owns it
+      // and no longer aliases `self`.
+      let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+      Err(PingError::new(msg))
     }
   }
 
@@ -116,6 +153,7 @@ impl Ping {
   /// all added hosts.
   ///
   pub fn add_host(&mut self, host_name: impl AsRef<CStr>) -> Result<()> {
+    self.block_on_pending();
     unsafe {
       let ret = ping_host_add(self.inner, host_name.as_ref().as_ptr());
       self.map_err(ret)
@@ -126,6 +164,7 @@ impl Ping {
   /// Returns error if the host is not resolved or not found.
   ///
   pub fn remove_host(&mut self, host_name: impl AsRef<CStr>) -> Result<()> {
+    self.block_on_pending();
     unsafe {
       let ret = ping_host_remove(self.inner, host_name.as_ref().as_ptr());
       self.map_err(ret)
@@ -135,7 +174,11 @@ impl Ping {
   /// Returns a [PingIter] object for iterating over all the associated host
   /// and get information.
   ///
-  pub fn iter(&self) -> PingIter<'_> {
+  /// Takes `&mut self` so that an outstanding [`Ping::send_poll`] worker can be
+  /// joined first: the iterator reads the same `pingobj_t` the worker writes,
+  /// so the two must never run concurrently.
+  pub fn iter(&mut self) -> PingIter<'_> {
+    self.block_on_pending();
     PingIter {
       inner: unsafe { ping_iterator_get(self.inner) },
       _phantom: Default::default(),
@@ -146,12 +189,170 @@ impl Ping {
   /// waiting for responses until timeout.
   /// Return the number of received echo messages on success.
   pub fn send(&mut self) -> Result<i32> {
+    self.block_on_pending();
     unsafe {
       let ret = ping_send(self.inner);
       self.map_err(ret)?;
       Ok(ret)
     }
   }
+
+  /// Send `count` rounds of probes to every added host, sleeping `interval`
+  /// between rounds, and fold the per-host latencies and timeouts into the
+  /// classic `ping` summary statistics keyed by the user-supplied hostname.
+  ///
+  /// Each round is a blocking [`send`](Ping::send); a round that fails to send
+  /// at all aborts the run and propagates the error. Within a round, a negative
+  /// latency means the probe timed out — it counts against packet loss but is
+  /// excluded from the RTT statistics (see [`HostStats`]).
+  pub fn run(
+    &mut self,
+    count: u32,
+    interval: Duration,
+  ) -> Result<HashMap<String, HostStats>> {
+    let mut stats: HashMap<String, HostStats> = HashMap::new();
+    for i in 0..count {
+      self.send()?;
+      for handle in self.iter() {
+        let entry = stats.entry(handle.get_hostname_user()).or_default();
+        entry.record(handle.get_latency());
+      }
+      if i + 1 < count {
+        std::thread::sleep(interval);
+      }
+    }
+    Ok(stats)
+  }
+
+  /// Non-blocking counterpart to [`Ping::send`], intended to be driven from an
+  /// event loop instead of dedicating a thread per `send()` call.
+  ///
+  /// liboping's public API only exposes the blocking `ping_send` — it offers no
+  /// `ping_send_async`/`ping_receive_all` decomposition and does not expose the
+  /// underlying ICMP socket, so we cannot hand out a `RawFd` to register with
+  /// mio/tokio directly. Instead the first poll offloads the blocking send onto
+  /// a worker thread; subsequent polls return [`Poll::Pending`] until the
+  /// worker finishes, at which point the result is delivered exactly like
+  /// [`Ping::send`]. A runtime can therefore `send_poll` many sessions and make
+  /// progress on all of them without any one blocking the others.
+  ///
+  /// ## Safety contract
+  ///
+  /// While a send is in flight (i.e. after a [`Poll::Pending`] and before the
+  /// next [`Poll::Ready`]) the worker thread holds the raw `pingobj_t` and its
+  /// socket fd. Because liboping's object is not thread safe, no other code may
+  /// touch it meanwhile — but this is enforced by the implementation, not left
+  /// to the caller: every other method (`send`, `add_host`, `remove_host`,
+  /// `iter`, the `set_*` setters, and [`Drop`]) first blocks on the outstanding
+  /// worker via an internal join, so interleaving a call simply waits for the
+  /// in-flight send to complete. The socket fd lives exactly as long as the
+  /// `Ping` object: it is opened lazily by liboping and closed by
+  /// `ping_destroy` in [`Drop`], so it must never be retained past the lifetime
+  /// of the `Ping`.
+  pub fn send_poll(&mut self) -> Poll<Result<i32>> {
+    if self.pending.is_none() {
+      let ptr = SendPtr(self.inner);
+      let (tx, rx) = mpsc::channel();
+      let handle = std::thread::spawn(move || {
+        // SAFETY: `self.pending` is now Some, so every other method on this
+        // Ping (including Drop) will block on this worker before touching
+        // `inner`, giving the worker exclusive access for the ping_send call.
+        let ret = unsafe { ping_send(ptr.0) };
+        let _ = tx.send(ret);
+      });
+      self.pending = Some((handle, rx));
+    }
+
+    match self.pending.as_ref().unwrap().1.try_recv() {
+      Ok(ret) => {
+        // The worker has returned; join it and reclaim `inner` before reading
+        // the (now-populated) error buffer via the shared mapping.
+        let (handle, _rx) = self.pending.take().unwrap();
+        let _ = handle.join();
+        let result = unsafe { self.map_err(ret).map(|()| ret) };
+        Poll::Ready(result)
+      }
+      Err(TryRecvError::Empty) => Poll::Pending,
+      Err(TryRecvError::Disconnected) => {
+        let (handle, _rx) = self.pending.take().unwrap();
+        let _ = handle.join();
+        Poll::Ready(Err(PingError::new(
+          "ping_send worker thread terminated unexpectedly".to_string(),
+        )))
+      }
+    }
+  }
+
+  /// Call `ping_setopt` with a pointer to a correctly-typed `value` and route
+  /// the return code through [`Ping::map_err`].
+  ///
+  /// SAFETY: `value` must point to a value of the type `ping_setopt` expects
+  /// for `option` (see the individual setters below) and must stay valid for
+  /// the duration of the call.
+  unsafe fn setopt<T>(&mut self, option: u32, value: *mut T) -> Result<()> {
+    self.block_on_pending();
+    let ret = ping_setopt(self.inner, option as i32, value as *mut c_void);
+    self.map_err(ret)
+  }
+
+  /// Set the time to wait for a response before a host is considered to have
+  /// dropped the probe (`PING_OPT_TIMEOUT`). liboping stores this as a `double`
+  /// number of seconds.
+  pub fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+    let mut secs = timeout.as_secs_f64();
+    unsafe { self.setopt(PING_OPT_TIMEOUT, &mut secs) }
+  }
+
+  /// Set the IP time-to-live of outgoing echo requests (`PING_OPT_TTL`), useful
+  /// for traceroute-like probing.
+  pub fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+    let mut ttl = ttl as i32;
+    unsafe { self.setopt(PING_OPT_TTL, &mut ttl) }
+  }
+
+  /// Force the address family of the session (`PING_OPT_AF`), so IPv6 targets
+  /// resolve and ping correctly.
+  pub fn set_address_family(&mut self, af: AddressFamily) -> Result<()> {
+    let mut af = af.as_raw();
+    unsafe { self.setopt(PING_OPT_AF, &mut af) }
+  }
+
+  /// Set the DSCP/TOS byte written into the IP header of outgoing requests
+  /// (`PING_OPT_QOS`).
+  pub fn set_qos(&mut self, qos: u8) -> Result<()> {
+    let mut qos = qos;
+    unsafe { self.setopt(PING_OPT_QOS, &mut qos) }
+  }
+
+  /// Set the payload carried by each echo request (`PING_OPT_DATA`). liboping
+  /// copies the bytes internally, so `payload` need not outlive the call.
+  /// The copy is taken as a C string: bytes from the first NUL in `payload`
+  /// onward are not sent.
+  pub fn set_payload(&mut self, payload: &[u8]) -> Result<()> {
+    // liboping reads the payload as a C string, so hand it a NUL-terminated
+    // copy it can `strdup`.
+    let mut data = Vec::with_capacity(payload.len() + 1);
+    data.extend_from_slice(payload);
+    data.push(0);
+    unsafe { self.setopt(PING_OPT_DATA, data.as_mut_ptr()) }
+  }
+
+  /// Bind outgoing requests to a specific source address (`PING_OPT_SOURCE`).
+  pub fn set_source(&mut self, source: &CStr) -> Result<()> {
+    unsafe { self.setopt(PING_OPT_SOURCE, source.as_ptr() as *mut c_char) }
+  }
+
+  /// Bind outgoing requests to a specific network device (`PING_OPT_DEVICE`).
+  pub fn set_device(&mut self, device: &CStr) -> Result<()> {
+    unsafe { self.setopt(PING_OPT_DEVICE, device.as_ptr() as *mut c_char) }
+  }
+
+  /// Set the firewall mark (`SO_MARK`) applied to the ICMP socket
+  /// (`PING_OPT_MARK`).
+  pub fn set_mark(&mut self, mark: u32) -> Result<()> {
+    let mut mark = mark;
+    unsafe { self.setopt(PING_OPT_MARK, &mut mark) }
+  }
 }
 
 impl Default for Ping {
@@ -162,6 +363,10 @@ impl Default for Ping {
 
 impl Drop for Ping {
   fn drop(&mut self) {
+    // Wait for any in-flight send_poll worker to finish before freeing the
+    // object, otherwise ping_destroy would race the worker's ping_send on a
+    // pointer it's about to free (use-after-free).
+    self.block_on_pending();
     // SAFETY: self.inner is returned by a valid call of
     // ping_construct, and cannot be modified or invalidated
     // during its lifetime.
@@ -263,6 +468,27 @@ impl<'a> IterInfoHandle<'a> {
     buf
   }
 
+  /// Read a fixed-width scalar `PING_INFO_*` field into a value of type `T`,
+  /// offering `size_of::<T>()` bytes as the buffer length. Suitable only for
+  /// fields whose C side writes at most `size_of::<T>()` bytes (e.g. the
+  /// `uint8_t` `PING_INFO_RECV_QOS`, which `memcpy`s `min(len, sizeof)`); a
+  /// field that demands a wider buffer would take liboping's too-small branch
+  /// and leave `buf` unwritten, so any nonzero return is treated as a hard
+  /// error rather than yielding uninitialized memory.
+  unsafe fn get_info_scalar<T: Copy>(&self, info: i32) -> T {
+    let mut buf = MaybeUninit::<T>::uninit();
+    let mut buf_len = std::mem::size_of::<T>() as u64;
+    let ret = ping_iterator_get_info(
+      self.inner,
+      info,
+      buf.as_mut_ptr() as *mut c_void,
+      &mut buf_len as *mut u64,
+    );
+
+    assert_eq!(0, ret, "ping_iterator_get_info failed for info {}", info);
+    buf.assume_init()
+  }
+
   unsafe fn get_info_int(&self, info: i32) -> i32 {
     let buf: i32 = 0;
     let buf_len: u64 = 32;
@@ -306,6 +532,128 @@ impl<'a> IterInfoHandle<'a> {
   pub fn get_latency(&self) -> f64 {
     unsafe { self.get_info_double(PING_INFO_LATENCY as i32) }
   }
+
+  /// Get the ICMP sequence number of the last probe sent to the associated
+  /// host (`PING_INFO_SEQUENCE`).
+  ///
+  pub fn get_sequence(&self) -> u16 {
+    // liboping's PING_INFO_SEQUENCE writes `sizeof(unsigned int)` (4 bytes) and
+    // refuses a smaller buffer, so read a full `i32` and narrow it.
+    unsafe { self.get_info_int(PING_INFO_SEQUENCE as i32) as u16 }
+  }
+
+  /// Get the TTL of the last received echo response (`PING_INFO_RECV_TTL`).
+  ///
+  pub fn get_received_ttl(&self) -> i32 {
+    unsafe { self.get_info_int(PING_INFO_RECV_TTL as i32) }
+  }
+
+  /// Get the TOS/QoS byte of the last received echo response
+  /// (`PING_INFO_RECV_QOS`).
+  ///
+  pub fn get_received_qos(&self) -> u8 {
+    unsafe { self.get_info_scalar::<u8>(PING_INFO_RECV_QOS as i32) }
+  }
+
+  /// Get the number of probes to this host that have timed out so far
+  /// (`PING_INFO_DROPPED`).
+  ///
+  pub fn get_dropped(&self) -> u32 {
+    unsafe { self.get_info_int(PING_INFO_DROPPED as i32) as u32 }
+  }
+
+  /// Get the address family liboping resolved for the associated host
+  /// (`PING_INFO_FAMILY`).
+  ///
+  pub fn get_family(&self) -> AddressFamily {
+    unsafe { AddressFamily::from_raw(self.get_info_int(PING_INFO_FAMILY as i32)) }
+  }
+}
+
+/// Running ping summary statistics for a single host, as accumulated by
+/// [`Ping::run`]. Mirrors the statistics block the `ping` command prints: the
+/// transmitted/received counts, the derived packet-loss percentage, and the
+/// min/avg/max/mdev of the measured round-trip times (in milliseconds).
+///
+/// The accumulator keeps a running sum and sum-of-squares so the standard
+/// deviation is computable in a single pass without retaining every sample.
+#[derive(Debug, Clone, Default)]
+pub struct HostStats {
+  transmitted: u32,
+  received: u32,
+  sum: f64,
+  sum_sq: f64,
+  min: f64,
+  max: f64,
+}
+
+impl HostStats {
+  /// Fold one measured latency into the accumulator. A negative `latency`
+  /// signals a timed-out probe: it is counted as a transmitted-but-lost packet
+  /// but kept out of the RTT sums.
+  fn record(&mut self, latency: f64) {
+    self.transmitted += 1;
+    if latency < 0.0 {
+      return;
+    }
+
+    self.received += 1;
+    self.sum += latency;
+    self.sum_sq += latency * latency;
+    if self.received == 1 || latency < self.min {
+      self.min = latency;
+    }
+    if latency > self.max {
+      self.max = latency;
+    }
+  }
+
+  /// Number of echo requests sent to the host.
+  pub fn transmitted(&self) -> u32 {
+    self.transmitted
+  }
+
+  /// Number of echo responses received from the host.
+  pub fn received(&self) -> u32 {
+    self.received
+  }
+
+  /// Percentage of probes that received no response, in `[0, 100]`.
+  pub fn packet_loss(&self) -> f64 {
+    if self.transmitted == 0 {
+      0.0
+    } else {
+      100.0 * (self.transmitted - self.received) as f64 / self.transmitted as f64
+    }
+  }
+
+  /// Minimum round-trip time in milliseconds, or `None` if nothing was
+  /// received.
+  pub fn min(&self) -> Option<f64> {
+    (self.received > 0).then_some(self.min)
+  }
+
+  /// Maximum round-trip time in milliseconds, or `None` if nothing was
+  /// received.
+  pub fn max(&self) -> Option<f64> {
+    (self.received > 0).then_some(self.max)
+  }
+
+  /// Mean round-trip time in milliseconds, or `None` if nothing was received.
+  pub fn avg(&self) -> Option<f64> {
+    (self.received > 0).then(|| self.sum / self.received as f64)
+  }
+
+  /// Standard deviation (mdev) of the round-trip times in milliseconds, or
+  /// `None` if nothing was received.
+  pub fn stddev(&self) -> Option<f64> {
+    (self.received > 0).then(|| {
+      let n = self.received as f64;
+      let mean = self.sum / n;
+      // Clamp to guard against tiny negative values from float rounding.
+      (self.sum_sq / n - mean * mean).max(0.0).sqrt()
+    })
+  }
 }
 
 #[cfg(test)]
@@ -365,4 +713,28 @@ mod tests {
     let c = handle.get_address();
     assert!(c.starts_with("127.0.0.1"));
   }
+
+  #[test]
+  fn host_stats_folds_latency_and_loss() {
+    let mut stats = HostStats::default();
+    stats.record(10.0);
+    stats.record(-1.0); // timeout: counts as loss, excluded from RTT
+    stats.record(20.0);
+
+    assert_eq!(3, stats.transmitted());
+    assert_eq!(2, stats.received());
+    assert!((stats.packet_loss() - 100.0 / 3.0).abs() < 1e-9);
+    assert_eq!(Some(10.0), stats.min());
+    assert_eq!(Some(20.0), stats.max());
+    assert_eq!(Some(15.0), stats.avg());
+    assert!((stats.stddev().unwrap() - 5.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn host_stats_empty_has_no_rtt() {
+    let stats = HostStats::default();
+    assert_eq!(0.0, stats.packet_loss());
+    assert_eq!(None, stats.avg());
+    assert_eq!(None, stats.stddev());
+  }
 }